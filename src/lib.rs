@@ -0,0 +1,3 @@
+pub mod metrics;
+pub mod pms5003;
+pub mod reconnect;
@@ -1,136 +1,528 @@
 use axum::{http::StatusCode, response::IntoResponse, routing::get, Router};
-use backoff::{future::retry, ExponentialBackoffBuilder};
 use clap::Parser;
 use futures::stream::StreamExt;
-use pms5003_exporter::{
-    metrics::{Metrics, METRICS_TTL},
-    pms5003,
-};
+use hyper::server::conn::Http;
+use pms5003_exporter::{metrics::Metrics, pms5003, reconnect::ConnectionState};
 use std::{
-    io,
+    fs,
+    future::poll_fn,
+    io::{self, BufReader},
     net::{IpAddr, Ipv4Addr, SocketAddr},
-    path::PathBuf,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
     sync::Arc,
+    task::{Context, Poll},
     time::Duration,
 };
 use tap::Tap;
 use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
     signal::unix::{signal, SignalKind},
     sync::{broadcast, RwLock},
 };
+use tokio_rustls::TlsAcceptor;
 use tokio_serial::SerialPortBuilderExt;
 use tokio_util::codec::Decoder;
+use tracing::Instrument;
 
 #[derive(Parser)]
 #[clap(name = "pms5003-exporter", version, author)]
 struct Cli {
-    serial_device_path: PathBuf,
+    /// Local serial device path (e.g. `/dev/ttyUSB0`), or a `tcp://host:port`
+    /// URL for a serial-to-TCP bridge. Repeatable to read from more than one
+    /// sensor; each is read on its own reconnecting task.
+    #[arg(required = true)]
+    serial_device_path: Vec<PathBuf>,
+
+    /// Label for the `sensor` metric tag, matched by position to
+    /// `serial_device_path`. Devices without a matching `--name` are labeled
+    /// with their device path instead.
+    #[arg(long = "name")]
+    name: Vec<String>,
 
     #[arg(long, default_value_t = Ipv4Addr::new(127, 0, 0, 1))]
     host: Ipv4Addr,
 
     #[arg(long, default_value_t = 3000)]
     port: u16,
+
+    /// PEM-encoded certificate chain to serve /metrics over HTTPS. Must be
+    /// passed together with `--tls-key`.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM-encoded private key matching `--tls-cert`.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Serve /metrics on a Unix domain socket at this path instead of
+    /// `--host`/`--port`. Any stale socket file at this path is removed
+    /// before binding.
+    #[arg(long, conflicts_with_all = ["host", "port"])]
+    unix_socket: Option<PathBuf>,
+
+    /// Output format for logs: human-readable text, or JSON for ingestion
+    /// by a log collector.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Give up on a sensor after this many consecutive reconnect attempts
+    /// instead of retrying forever. Unset means retry indefinitely.
+    #[arg(long)]
+    max_retries: Option<u32>,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+fn init_tracing(format: LogFormat) {
+    let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+
+    match format {
+        LogFormat::Text => tracing_subscriber::fmt().with_env_filter(env_filter).init(),
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .with_env_filter(env_filter)
+            .init(),
+    }
 }
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
+    init_tracing(cli.log_format);
 
     let mut sigterm = signal(SignalKind::terminate()).unwrap();
     let (notify_shutdown, _) = broadcast::channel::<()>(1);
     let metrics = Arc::new(RwLock::new(Metrics::new()));
 
+    let tls_config = cli
+        .tls_cert
+        .as_deref()
+        .zip(cli.tls_key.as_deref())
+        .map(|(cert, key)| {
+            Arc::new(
+                load_tls_config(cert, key).unwrap_or_else(|error| {
+                    panic!("failed to load TLS certificate/key: {:?}", error)
+                }),
+            )
+        });
+
+    let socket = match cli.unix_socket {
+        Some(path) => Socket::Unix(path),
+        None => Socket::Tcp(SocketAddr::new(IpAddr::V4(cli.host), cli.port)),
+    };
+
     let server_task = tokio::spawn(serve(
-        SocketAddr::new(IpAddr::V4(cli.host), cli.port),
+        socket,
         Arc::clone(&metrics),
         notify_shutdown.subscribe(),
+        tls_config,
     ));
 
+    let mut read_tasks = tokio::task::JoinSet::new();
+    for (index, device) in cli.serial_device_path.iter().enumerate() {
+        let sensor = cli
+            .name
+            .get(index)
+            .cloned()
+            .unwrap_or_else(|| device.to_string_lossy().into_owned());
+        let device = device.to_string_lossy().into_owned();
+        let metrics = Arc::clone(&metrics);
+        let max_retries = cli.max_retries;
+        let shutdown = notify_shutdown.subscribe();
+        read_tasks.spawn(async move {
+            read(&device, &sensor, metrics, max_retries, shutdown).await
+        });
+    }
+
     tokio::select! {
-        _ = read(cli.serial_device_path.to_str().unwrap(), Arc::clone(&metrics)) => {},
         _ = sigterm.recv() => {
-            println!("received sigterm, stopping");
+            tracing::info!("received sigterm, stopping");
         }
         _ = tokio::signal::ctrl_c() => {
-            println!("received ctrl-c, stopping");
+            tracing::info!("received ctrl-c, stopping");
         }
     }
 
     drop(notify_shutdown);
+    while read_tasks.join_next().await.is_some() {}
     let _ = server_task.await;
 }
 
+/// Drives a single sensor's connection through an explicit
+/// `Connecting -> Reading -> Backoff -> {Connecting, Stopped}` state
+/// machine, recording each transition in `Metrics` so the link's health is
+/// scrapeable instead of only visible in logs. `notify_shutdown` is checked
+/// both while reading frames and while backed off, so shutdown does not wait
+/// for the stream to end or for a backoff delay to run out. It is not checked
+/// while the stream is being opened (`Connecting`).
 async fn read(
     serial_device: &str,
+    sensor: &str,
     metrics: Arc<RwLock<Metrics>>,
-) -> Result<(), tokio_serial::Error> {
-    let backoff = ExponentialBackoffBuilder::default()
-        .with_max_interval(Duration::from_millis(5000))
-        .with_max_elapsed_time(None)
-        .build();
-
-    retry::<(), _, _, _, _>(backoff, || async {
-        println!("opening serial port");
+    max_retries: Option<u32>,
+    mut notify_shutdown: broadcast::Receiver<()>,
+) {
+    async move {
+        let mut attempt: u32 = 0;
+
+        loop {
+            set_state(&metrics, sensor, ConnectionState::Connecting).await;
+
+            let stream = match open_sensor_stream(serial_device).await {
+                Ok(stream) => stream,
+                Err(error) => {
+                    metrics.write().await.set_last_error(sensor, error.to_string());
+                    let outcome =
+                        backoff_or_stop(&metrics, sensor, &mut attempt, max_retries, &mut notify_shutdown)
+                            .await;
+                    match outcome {
+                        ControlFlow::Continue(()) => continue,
+                        ControlFlow::Break(()) => return,
+                    }
+                }
+            };
+
+            set_state(&metrics, sensor, ConnectionState::Reading).await;
+            attempt = 0;
+            tracing::info!("port open");
+            let mut reader = pms5003::Pms5003Codec::new().framed(stream);
+
+            let stream_ended = loop {
+                tokio::select! {
+                    frame = reader.next() => {
+                        match frame {
+                            Some(Ok(frame)) => {
+                                tracing::debug!(?frame, "frame received");
+                                metrics.write().await.update(sensor, &frame);
+                            }
+                            Some(Err(error)) => tracing::warn!(?error, "error decoding frame"),
+                            None => break true,
+                        }
+                    }
+                    _ = notify_shutdown.recv() => {
+                        set_state(&metrics, sensor, ConnectionState::Stopped).await;
+                        tracing::info!("shutdown requested, stopping reconnect loop");
+                        return;
+                    }
+                }
+            };
+
+            if stream_ended {
+                metrics
+                    .write()
+                    .await
+                    .set_last_error(sensor, "serial read stream ended");
+            }
+
+            let outcome =
+                backoff_or_stop(&metrics, sensor, &mut attempt, max_retries, &mut notify_shutdown).await;
+            match outcome {
+                ControlFlow::Continue(()) => continue,
+                ControlFlow::Break(()) => return,
+            }
+        }
+    }
+    .instrument(tracing::info_span!("read", device = serial_device, sensor))
+    .await
+}
+
+async fn set_state(metrics: &Arc<RwLock<Metrics>>, sensor: &str, state: ConnectionState) {
+    metrics.write().await.set_state(sensor, state);
+}
+
+/// Transitions into `Backoff`, recording the attempt count, and either
+/// sleeps out the backoff delay (`ControlFlow::Continue`, try again) or
+/// gives up because `max_retries` was exceeded or shutdown was requested
+/// (`ControlFlow::Break`, caller should return).
+async fn backoff_or_stop(
+    metrics: &Arc<RwLock<Metrics>>,
+    sensor: &str,
+    attempt: &mut u32,
+    max_retries: Option<u32>,
+    notify_shutdown: &mut broadcast::Receiver<()>,
+) -> ControlFlow<()> {
+    *attempt += 1;
+
+    if max_retries.is_some_and(|max| *attempt > max) {
+        set_state(metrics, sensor, ConnectionState::Stopped).await;
+        tracing::warn!(attempt = *attempt, "max retries exceeded, giving up");
+        return ControlFlow::Break(());
+    }
+
+    let delay = backoff_delay(*attempt);
+    set_state(
+        metrics,
+        sensor,
+        ConnectionState::Backoff { attempt: *attempt },
+    )
+    .await;
+    tracing::warn!(attempt = *attempt, ?delay, "retrying after connection error");
+
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => ControlFlow::Continue(()),
+        _ = notify_shutdown.recv() => {
+            set_state(metrics, sensor, ConnectionState::Stopped).await;
+            tracing::info!("shutdown requested during backoff, stopping reconnect loop");
+            ControlFlow::Break(())
+        }
+    }
+}
+
+fn backoff_delay(attempt: u32) -> Duration {
+    let millis = 500u64.saturating_mul(1u64 << attempt.min(4));
+    Duration::from_millis(millis.min(5000))
+}
+
+/// Opens the byte stream the sensor frames are read from. `serial_device`
+/// is either a local device path (e.g. `/dev/ttyUSB0`) or a `tcp://host:port`
+/// URL pointing at a serial-to-TCP bridge; either way the resulting stream
+/// is fed through the same `Pms5003Codec`.
+async fn open_sensor_stream(serial_device: &str) -> Result<SensorStream, tokio_serial::Error> {
+    if let Some(addr) = serial_device.strip_prefix("tcp://") {
+        tracing::info!(%addr, "opening tcp connection");
+        let stream = TcpStream::connect(addr).await.map_err(|error| {
+            tracing::warn!(%error, "failed to open tcp connection");
+            tokio_serial::Error::new(tokio_serial::ErrorKind::Io(error.kind()), error.to_string())
+        })?;
+        Ok(SensorStream::Tcp(stream))
+    } else {
+        tracing::info!("opening serial port");
         let port = tokio_serial::new(serial_device, 9600)
             .open_native_async()
             .tap(|result| {
                 if let Err(error) = result {
-                    println!("Failed to open serial port: {:?}", error);
+                    tracing::warn!(%error, "failed to open serial port");
                 }
             })?;
+        Ok(SensorStream::Serial(port))
+    }
+}
 
-        let mut reader = pms5003::Pms5003Codec::new().framed(port);
-        println!("port open");
+/// A sensor byte stream, either a local serial port or a TCP connection to
+/// a serial-to-TCP bridge. Implements `AsyncRead`/`AsyncWrite` by dispatching
+/// to whichever transport is active so `Pms5003Codec` can stay transport-agnostic.
+enum SensorStream {
+    Serial(tokio_serial::SerialStream),
+    Tcp(tokio::net::TcpStream),
+}
 
-        while let Some(frame) = reader.next().await {
-            match frame {
-                Ok(frame) => {
-                    println!("frame received: {:?}", frame);
-                    let mut metrics = metrics.write().await;
-                    metrics.update(&frame);
-                }
-                Err(error) => println!("Error reading frame: {:?}", error),
-            }
+impl AsyncRead for SensorStream {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        match self.get_mut() {
+            SensorStream::Serial(stream) => std::pin::Pin::new(stream).poll_read(cx, buf),
+            SensorStream::Tcp(stream) => std::pin::Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for SensorStream {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<io::Result<usize>> {
+        match self.get_mut() {
+            SensorStream::Serial(stream) => std::pin::Pin::new(stream).poll_write(cx, buf),
+            SensorStream::Tcp(stream) => std::pin::Pin::new(stream).poll_write(cx, buf),
         }
+    }
 
-        Err(backoff::Error::transient(tokio_serial::Error::new(
-            tokio_serial::ErrorKind::Io(io::ErrorKind::ConnectionReset),
-            "Serial read stream ended",
-        )))
-    })
-    .await
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        match self.get_mut() {
+            SensorStream::Serial(stream) => std::pin::Pin::new(stream).poll_flush(cx),
+            SensorStream::Tcp(stream) => std::pin::Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        match self.get_mut() {
+            SensorStream::Serial(stream) => std::pin::Pin::new(stream).poll_shutdown(cx),
+            SensorStream::Tcp(stream) => std::pin::Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Where the `/metrics` endpoint is bound.
+enum Socket {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+/// Something that yields accepted connections, abstracting over the
+/// underlying transport (TCP, Unix domain socket, and in future e.g. vsock)
+/// so `serve`'s accept loop only needs to be written once.
+trait Listener {
+    type Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Self::Conn>>;
+}
+
+impl Listener for TcpListener {
+    type Conn = TcpStream;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Self::Conn>> {
+        match TcpListener::poll_accept(self, cx) {
+            Poll::Ready(result) => Poll::Ready(result.map(|(stream, _)| stream)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Listener for UnixListener {
+    type Conn = UnixStream;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Self::Conn>> {
+        match UnixListener::poll_accept(self, cx) {
+            Poll::Ready(result) => Poll::Ready(result.map(|(stream, _)| stream)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
 }
 
 async fn serve(
-    addr: SocketAddr,
+    socket: Socket,
     metrics: Arc<RwLock<Metrics>>,
-    mut notify_shutdown: broadcast::Receiver<()>,
+    notify_shutdown: broadcast::Receiver<()>,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
 ) {
     let app = Router::new()
         .route("/metrics", get(move || handler(Arc::clone(&metrics))))
         .fallback(handler_404);
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(async move {
-            let _ = notify_shutdown.recv().await;
-            println!("stopping server");
-        })
-        .await
-        .unwrap();
-    println!("server stopped");
+    match socket {
+        Socket::Tcp(addr) => {
+            let listener = TcpListener::bind(addr).await.unwrap();
+            tracing::info!(%addr, "metrics endpoint listening");
+            run_server(listener, app, tls_config, notify_shutdown).await;
+        }
+        Socket::Unix(path) => {
+            if path.exists() {
+                fs::remove_file(&path).unwrap();
+            }
+            let listener = UnixListener::bind(&path).unwrap();
+            tracing::info!(path = %path.display(), "metrics endpoint listening");
+            run_server(listener, app, tls_config, notify_shutdown).await;
+        }
+    }
+    tracing::info!("server stopped");
+}
+
+/// Drives the accept loop for any `Listener`, optionally promoting each
+/// accepted connection to TLS before handing it to hyper.
+async fn run_server<L: Listener>(
+    mut listener: L,
+    app: Router,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    mut notify_shutdown: broadcast::Receiver<()>,
+) {
+    let acceptor = tls_config.map(TlsAcceptor::from);
+    let mut connections = tokio::task::JoinSet::new();
+
+    loop {
+        tokio::select! {
+            accepted = poll_fn(|cx| listener.poll_accept(cx)) => {
+                let stream = match accepted {
+                    Ok(stream) => stream,
+                    Err(error) => {
+                        tracing::warn!(%error, "failed to accept connection");
+                        continue;
+                    }
+                };
+
+                let app = app.clone();
+                let acceptor = acceptor.clone();
+                connections.spawn(async move {
+                    match acceptor {
+                        Some(acceptor) => match acceptor.accept(stream).await {
+                            Ok(tls_stream) => serve_conn(tls_stream, app).await,
+                            Err(error) => tracing::warn!(%error, "tls handshake failed"),
+                        },
+                        None => serve_conn(stream, app).await,
+                    }
+                });
+            }
+            _ = notify_shutdown.recv() => {
+                tracing::info!("stopping server");
+                break;
+            }
+        }
+    }
+
+    tracing::info!(in_flight = connections.len(), "waiting for in-flight connections");
+    while connections.join_next().await.is_some() {}
+}
+
+async fn serve_conn<S>(stream: S, app: Router)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    async move {
+        tracing::debug!("connection opened");
+        if let Err(error) = Http::new().serve_connection(stream, app).await {
+            tracing::warn!(%error, "error serving connection");
+        }
+        tracing::debug!("connection closed");
+    }
+    .instrument(tracing::info_span!("connection"))
+    .await
+}
+
+/// Loads a PEM certificate chain and private key into a rustls server
+/// configuration suitable for `TlsAcceptor`.
+fn load_tls_config(cert_path: &Path, key_path: &Path) -> io::Result<rustls::ServerConfig> {
+    let mut cert_reader = BufReader::new(fs::File::open(cert_path)?);
+    let certs = rustls_pemfile::certs(&mut cert_reader)?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+
+    let mut key_reader = BufReader::new(fs::File::open(key_path)?);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut key_reader)? {
+            Some(
+                rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::ECKey(key),
+            ) => break rustls::PrivateKey(key),
+            Some(_) => continue,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "no PKCS#1, PKCS#8, or SEC1 private key found in tls-key file",
+                ))
+            }
+        }
+    };
+
+    rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
 }
 
 async fn handler(metrics: Arc<RwLock<Metrics>>) -> Result<String, StatusCode> {
     let metrics = metrics.read().await;
 
-    if metrics.last_update.elapsed() > METRICS_TTL {
-        return Ok(String::new());
-    }
-
     metrics.encode().map_err(|error| {
-        println!("Error while encoding metrics: {:?}", error);
+        tracing::warn!(%error, "error encoding metrics");
         StatusCode::INTERNAL_SERVER_ERROR
     })
 }
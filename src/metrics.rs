@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::pms5003::Frame;
+use crate::reconnect::ConnectionState;
+
+/// How long a sensor's latest frame is considered fresh enough to still be
+/// scraped, before it's silently dropped from `encode()`.
+pub const METRICS_TTL: Duration = Duration::from_secs(60);
+
+struct DeviceMetrics {
+    last_update: Instant,
+    frame: Option<Frame>,
+    state: ConnectionState,
+    reconnect_attempts: u64,
+    last_error: Option<String>,
+}
+
+impl DeviceMetrics {
+    fn new() -> Self {
+        Self {
+            last_update: Instant::now(),
+            frame: None,
+            state: ConnectionState::Connecting,
+            reconnect_attempts: 0,
+            last_error: None,
+        }
+    }
+}
+
+/// Latest reading and reconnection state per sensor, keyed by the `sensor`
+/// label (the device path or a user-supplied `--name`), so a single
+/// exporter can report on a whole rack of PMS5003 units.
+#[derive(Default)]
+pub struct Metrics {
+    devices: HashMap<String, DeviceMetrics>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, sensor: &str, frame: &Frame) {
+        let device = self.device_mut(sensor);
+        device.frame = Some(*frame);
+        device.last_update = Instant::now();
+    }
+
+    pub fn set_state(&mut self, sensor: &str, state: ConnectionState) {
+        let device = self.device_mut(sensor);
+        if let ConnectionState::Backoff { .. } = state {
+            device.reconnect_attempts += 1;
+        }
+        device.state = state;
+    }
+
+    pub fn set_last_error(&mut self, sensor: &str, error: impl Into<String>) {
+        self.device_mut(sensor).last_error = Some(error.into());
+    }
+
+    fn device_mut(&mut self, sensor: &str) -> &mut DeviceMetrics {
+        self.devices
+            .entry(sensor.to_string())
+            .or_insert_with(DeviceMetrics::new)
+    }
+
+    pub fn encode(&self) -> Result<String, std::fmt::Error> {
+        let mut out = String::new();
+
+        write_up_gauge(&mut out, &self.devices)?;
+        write_reconnect_counter(&mut out, &self.devices)?;
+        write_last_error_info(&mut out, &self.devices)?;
+
+        let active: Vec<_> = self
+            .devices
+            .iter()
+            .filter(|(_, device)| {
+                device.frame.is_some() && device.last_update.elapsed() <= METRICS_TTL
+            })
+            .collect();
+
+        write_gauge(&mut out, "pms5003_pm1_0_cf1", &active, |f| f.pm1_0_cf1)?;
+        write_gauge(&mut out, "pms5003_pm2_5_cf1", &active, |f| f.pm2_5_cf1)?;
+        write_gauge(&mut out, "pms5003_pm10_0_cf1", &active, |f| f.pm10_0_cf1)?;
+        write_gauge(&mut out, "pms5003_pm1_0_atm", &active, |f| f.pm1_0_atm)?;
+        write_gauge(&mut out, "pms5003_pm2_5_atm", &active, |f| f.pm2_5_atm)?;
+        write_gauge(&mut out, "pms5003_pm10_0_atm", &active, |f| f.pm10_0_atm)?;
+        write_gauge(&mut out, "pms5003_particles_0_3um", &active, |f| {
+            f.particles_0_3um
+        })?;
+        write_gauge(&mut out, "pms5003_particles_0_5um", &active, |f| {
+            f.particles_0_5um
+        })?;
+        write_gauge(&mut out, "pms5003_particles_1_0um", &active, |f| {
+            f.particles_1_0um
+        })?;
+        write_gauge(&mut out, "pms5003_particles_2_5um", &active, |f| {
+            f.particles_2_5um
+        })?;
+        write_gauge(&mut out, "pms5003_particles_5_0um", &active, |f| {
+            f.particles_5_0um
+        })?;
+        write_gauge(&mut out, "pms5003_particles_10_0um", &active, |f| {
+            f.particles_10_0um
+        })?;
+
+        Ok(out)
+    }
+}
+
+fn write_gauge(
+    out: &mut String,
+    name: &str,
+    devices: &[(&String, &DeviceMetrics)],
+    value_of: impl Fn(&Frame) -> u16,
+) -> Result<(), std::fmt::Error> {
+    writeln!(out, "# TYPE {name} gauge")?;
+    for (sensor, device) in devices {
+        writeln!(
+            out,
+            "{name}{{sensor=\"{}\"}} {value}",
+            escape_label_value(sensor),
+            value = value_of(device.frame.as_ref().expect("filtered for a frame above"))
+        )?;
+    }
+    Ok(())
+}
+
+fn write_up_gauge(
+    out: &mut String,
+    devices: &HashMap<String, DeviceMetrics>,
+) -> Result<(), std::fmt::Error> {
+    writeln!(out, "# TYPE pms5003_up gauge")?;
+    for (sensor, device) in devices {
+        writeln!(
+            out,
+            "pms5003_up{{sensor=\"{}\"}} {}",
+            escape_label_value(sensor),
+            device.state.is_up() as u8
+        )?;
+    }
+    Ok(())
+}
+
+fn write_reconnect_counter(
+    out: &mut String,
+    devices: &HashMap<String, DeviceMetrics>,
+) -> Result<(), std::fmt::Error> {
+    writeln!(out, "# TYPE pms5003_reconnect_attempts_total counter")?;
+    for (sensor, device) in devices {
+        writeln!(
+            out,
+            "pms5003_reconnect_attempts_total{{sensor=\"{}\"}} {}",
+            escape_label_value(sensor),
+            device.reconnect_attempts
+        )?;
+    }
+    Ok(())
+}
+
+fn write_last_error_info(
+    out: &mut String,
+    devices: &HashMap<String, DeviceMetrics>,
+) -> Result<(), std::fmt::Error> {
+    writeln!(out, "# TYPE pms5003_last_error_info gauge")?;
+    for (sensor, device) in devices {
+        if let Some(error) = &device.last_error {
+            writeln!(
+                out,
+                "pms5003_last_error_info{{sensor=\"{}\",error=\"{}\"}} 1",
+                escape_label_value(sensor),
+                escape_label_value(error)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,93 @@
+use bytes::Buf;
+use tokio_util::codec::Decoder;
+
+const FRAME_MAGIC: [u8; 2] = [0x42, 0x4D];
+const FRAME_LEN: usize = 32;
+
+/// A single decoded reading from a PMS5003 particulate matter sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub pm1_0_cf1: u16,
+    pub pm2_5_cf1: u16,
+    pub pm10_0_cf1: u16,
+    pub pm1_0_atm: u16,
+    pub pm2_5_atm: u16,
+    pub pm10_0_atm: u16,
+    pub particles_0_3um: u16,
+    pub particles_0_5um: u16,
+    pub particles_1_0um: u16,
+    pub particles_2_5um: u16,
+    pub particles_5_0um: u16,
+    pub particles_10_0um: u16,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("frame checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: u16, actual: u16 },
+    #[error("unexpected frame magic bytes: {0:?}")]
+    BadMagic([u8; 2]),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// A `tokio_util::codec::Decoder` that turns a raw PMS5003 byte stream into
+/// [`Frame`]s, regardless of whether the bytes arrive over a serial port or
+/// a TCP socket.
+#[derive(Debug, Default)]
+pub struct Pms5003Codec;
+
+impl Pms5003Codec {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Decoder for Pms5003Codec {
+    type Item = Frame;
+    type Error = DecodeError;
+
+    fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // Resync on the two magic bytes before we have a full frame buffered.
+        while src.len() >= 2 && src[0..2] != FRAME_MAGIC {
+            src.advance(1);
+        }
+
+        if src.len() < FRAME_LEN {
+            return Ok(None);
+        }
+
+        let frame = &src[..FRAME_LEN];
+        let checksum = frame
+            .iter()
+            .take(FRAME_LEN - 2)
+            .fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+        let expected = u16::from_be_bytes([frame[FRAME_LEN - 2], frame[FRAME_LEN - 1]]);
+        if checksum != expected {
+            src.advance(2); // drop the magic bytes and try to resync
+            return Err(DecodeError::ChecksumMismatch {
+                expected,
+                actual: checksum,
+            });
+        }
+
+        let read_u16 = |offset: usize| u16::from_be_bytes([frame[offset], frame[offset + 1]]);
+        let decoded = Frame {
+            pm1_0_cf1: read_u16(4),
+            pm2_5_cf1: read_u16(6),
+            pm10_0_cf1: read_u16(8),
+            pm1_0_atm: read_u16(10),
+            pm2_5_atm: read_u16(12),
+            pm10_0_atm: read_u16(14),
+            particles_0_3um: read_u16(16),
+            particles_0_5um: read_u16(18),
+            particles_1_0um: read_u16(20),
+            particles_2_5um: read_u16(22),
+            particles_5_0um: read_u16(24),
+            particles_10_0um: read_u16(26),
+        };
+
+        src.advance(FRAME_LEN);
+        Ok(Some(decoded))
+    }
+}
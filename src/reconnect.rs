@@ -0,0 +1,17 @@
+/// Reconnection state for a single sensor's read loop, recorded in
+/// [`crate::metrics::Metrics`] so Prometheus can alert when a sensor link
+/// goes down instead of the read loop silently retrying forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Reading,
+    Backoff { attempt: u32 },
+    Stopped,
+}
+
+impl ConnectionState {
+    /// Whether this state counts as "up" for the `pms5003_up` gauge.
+    pub fn is_up(self) -> bool {
+        matches!(self, ConnectionState::Reading)
+    }
+}